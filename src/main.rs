@@ -1,6 +1,9 @@
 use clap::{command, Parser, Subcommand};
 use hashes::Hashes;
+use peer::PeerConnection;
 use peers::Peers;
+use rand::distributions::Alphanumeric;
+use rand::Rng;
 use serde::{self, Deserialize, Serialize};
 use sha1::{Digest, Sha1};
 use std::path::PathBuf;
@@ -17,6 +20,23 @@ enum Command {
     Decode { value: String },
     Info { torrent: PathBuf },
     Peers { torrent: PathBuf },
+    DownloadPiece {
+        #[arg(short, long)]
+        output: PathBuf,
+        torrent: PathBuf,
+        piece: usize,
+    },
+    Download {
+        #[arg(short, long)]
+        output: PathBuf,
+        torrent: PathBuf,
+    },
+    Magnet {
+        uri: String,
+        /// if given, download the whole torrent here once metadata and peers are resolved
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
 }
 
 fn interperet_value(value: serde_bencode::value::Value) -> serde_json::Value {
@@ -46,7 +66,7 @@ fn decode_bencoded_value(input: &str) -> serde_json::Value {
     interperet_value(serde_bencode::from_str(&input).unwrap())
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 struct Url(reqwest::Url);
 
 impl Url {
@@ -76,26 +96,57 @@ impl Serialize for Url {
     }
 }
 
-#[derive(Deserialize, Serialize, Debug)]
-struct Torrent {
+#[derive(Deserialize, Debug)]
+struct TorrentFile {
     /// URL to a "tracker", which is a central server that keeps track of peers participating in the sharing of a torrent.
     announce: Url,
+    /// backup trackers, grouped into tiers (BEP 12). Every tier is announced to, in order;
+    /// trackers within a tier are shuffled and tried in order until one responds.
+    #[serde(rename = "announce-list")]
+    announce_list: Option<Vec<Vec<Url>>>,
+    info: Info,
+}
+
+#[derive(Debug)]
+struct Torrent {
+    announce: Url,
+    announce_list: Option<Vec<Vec<Url>>>,
     info: Info,
+    /// the exact bencoded bytes of the `info` dict as they appeared in the original file.
+    /// We keep these around (rather than re-serializing `info`) because `Info` doesn't model
+    /// every key a real-world torrent might carry (`private`, `source`, `md5sum`, ...), so
+    /// re-encoding it would silently produce the wrong hash.
+    info_raw: Vec<u8>,
 }
 
 impl Torrent {
+    pub fn from_bytes(buf: &[u8]) -> anyhow::Result<Self> {
+        let file: TorrentFile = serde_bencode::from_bytes(buf)?;
+        file.info.validate()?;
+        let info_raw = raw_bencode::find_dict_value(buf, "info")
+            .ok_or_else(|| anyhow::anyhow!("torrent file has no \"info\" dict"))?
+            .to_vec();
+        Ok(Torrent {
+            announce: file.announce,
+            announce_list: file.announce_list,
+            info: file.info,
+            info_raw,
+        })
+    }
+
     pub fn info_hash(&self) -> hashes::Hash {
         let mut hasher = Sha1::new();
-        let encoded_info = serde_bencode::to_bytes(&self.info).unwrap();
-        hasher.update(encoded_info);
+        hasher.update(&self.info_raw);
         hasher.finalize().into()
     }
 }
 
-#[derive(Deserialize, Serialize, Debug)]
+#[derive(Deserialize, Debug)]
 struct Info {
-    /// size of the file in bytes, for single-file torrents
-    length: u64,
+    /// files in the torrent, for multi-file torrents. Mutually exclusive with `length`.
+    files: Option<Vec<File>>,
+    /// size of the file in bytes, for single-file torrents. Mutually exclusive with `files`.
+    length: Option<u64>,
     /// suggested name to save the file / directory as
     name: String,
     /// number of bytes in each piece
@@ -105,6 +156,38 @@ struct Info {
     pieces: Hashes,
 }
 
+impl Info {
+    /// checks that exactly one of `files`/`length` is present, as the spec requires.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        match (&self.files, &self.length) {
+            (Some(_), Some(_)) => {
+                anyhow::bail!("info dict has both \"files\" and \"length\"; they are mutually exclusive")
+            }
+            (None, None) => {
+                anyhow::bail!("info dict has neither \"files\" nor \"length\"")
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// total size of the torrent's content in bytes, whether it's a single file
+    /// or the sum of all files in a multi-file torrent
+    pub fn total_length(&self) -> u64 {
+        match &self.files {
+            Some(files) => files.iter().map(|file| file.length).sum(),
+            None => self.length.unwrap_or(0),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct File {
+    /// length of the file in bytes
+    length: u64,
+    /// path components of the file, relative to the torrent's `name` directory
+    path: Vec<String>,
+}
+
 #[derive(Debug)]
 struct Client<'a> {
     peer_id: String,
@@ -113,47 +196,165 @@ struct Client<'a> {
     downloaded: u64,
     left: u64,
     torrent: &'a Torrent,
+    /// trackers to announce to, grouped into tiers (BEP 12). Falls back to a single tier
+    /// containing just `torrent.announce` when the torrent has no `announce-list`. Mutable
+    /// because a working tracker is promoted to the front of its tier after a successful
+    /// announce, as the spec prescribes.
+    announce_tiers: Vec<Vec<Url>>,
 }
 
 impl<'a> Client<'a> {
     pub fn new(torrent: &'a Torrent) -> Self {
+        Self::with_peer_id_prefix(torrent, DEFAULT_PEER_ID_PREFIX)
+    }
+
+    /// like `new`, but overrides the Azureus-style client prefix embedded in the generated
+    /// peer id (see `generate_peer_id`) instead of using this client's own.
+    pub fn with_peer_id_prefix(torrent: &'a Torrent, prefix: &str) -> Self {
+        let mut announce_tiers = match &torrent.announce_list {
+            Some(tiers) if !tiers.is_empty() => tiers.clone(),
+            _ => vec![vec![torrent.announce.clone()]],
+        };
+
+        // BEP 12: shuffle each tier once, when the torrent is loaded. after that, a
+        // successful tracker is promoted to the front of its tier (see `get_peers`) and we
+        // never reshuffle, so that promotion sticks across calls.
+        for tier in &mut announce_tiers {
+            shuffle(tier);
+        }
+
         Self {
-            // TODO: generate a random peer id
-            peer_id: "00112233445566778899".to_owned(),
-            left: torrent.info.length,
+            peer_id: generate_peer_id(prefix),
+            left: torrent.info.total_length(),
             port: 6881,
             uploaded: 0,
             downloaded: 0,
-            torrent: torrent,
+            torrent,
+            announce_tiers,
         }
     }
 
-    pub async fn get_peers(&self) -> anyhow::Result<Peers> {
-        let client = reqwest::Client::new();
+    /// the 20-byte peer id, as required by the handshake and tracker requests
+    pub fn peer_id_bytes(&self) -> anyhow::Result<[u8; 20]> {
+        self.peer_id
+            .as_bytes()
+            .try_into()
+            .map_err(|_| anyhow::anyhow!("peer id must be exactly 20 bytes"))
+    }
 
-        let mut request = client.get(self.torrent.announce.value().clone()).build()?;
+    /// announces to each tier in order, trying shuffled trackers within a tier until one
+    /// responds, and merges the peers every tier that responded returned (deduplicated by
+    /// ip:port) so one dead tracker doesn't make the whole call fail.
+    pub async fn get_peers(&mut self) -> anyhow::Result<Peers> {
+        let mut peers = Vec::new();
+        let mut seen = std::collections::HashSet::new();
+        let mut any_tracker_responded = false;
 
-        let query = serde_urlencoded::to_string(&TrackerRequest {
-            compact: 1,
-            downloaded: self.downloaded as usize,
-            left: self.left as usize,
-            peer_id: self.peer_id.clone(),
-            port: self.port,
-            uploaded: self.uploaded as usize,
-        })?;
+        for tier in &mut self.announce_tiers {
+            for i in 0..tier.len() {
+                let result = get_peers_from_tracker(
+                    tier[i].value(),
+                    self.torrent.info_hash(),
+                    &self.peer_id,
+                    self.port,
+                    self.uploaded,
+                    self.downloaded,
+                    self.left,
+                )
+                .await;
 
-        request.url_mut().set_query(Some(&format!(
-            "info_hash={}&{}",
-            urlencode(&self.torrent.info_hash()).as_str(),
-            query,
-        )));
+                let Ok(tier_peers) = result else { continue };
 
-        let body = client.execute(request).await?.bytes().await?;
+                any_tracker_responded = true;
+                tier.swap(0, i);
+                for peer in tier_peers.0 {
+                    if seen.insert(peer.to_string()) {
+                        peers.push(peer);
+                    }
+                }
+                break;
+            }
+        }
 
-        Ok(serde_bencode::from_bytes::<TrackerResponse>(body.as_ref())?.peers)
+        anyhow::ensure!(any_tracker_responded, "no tracker in any tier returned peers");
+        Ok(Peers(peers))
     }
 }
 
+/// the Azureus-style client identifier embedded at the start of every peer id this client
+/// generates: "RS" for rust, "0001" for the current (pre-1.0) version.
+const DEFAULT_PEER_ID_PREFIX: &str = "-RS0001-";
+
+const PEER_ID_LEN: usize = 20;
+
+/// generates a spec-compliant peer id for use in tracker requests and peer handshakes,
+/// following the Azureus-style convention of a fixed client prefix followed by random
+/// alphanumeric padding out to 20 bytes.
+fn generate_peer_id(prefix: &str) -> String {
+    let suffix_len = PEER_ID_LEN.saturating_sub(prefix.len());
+    let suffix: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(suffix_len)
+        .map(char::from)
+        .collect();
+    format!("{prefix}{suffix}")
+}
+
+/// a random u64, used for UDP tracker transaction ids and announce keys.
+fn random_u64() -> u64 {
+    rand::thread_rng().gen()
+}
+
+/// in-place Fisher-Yates shuffle, used to randomize the tracker order within an
+/// announce-list tier (BEP 12).
+fn shuffle<T>(items: &mut [T]) {
+    for i in (1..items.len()).rev() {
+        let j = rand::thread_rng().gen_range(0..=i);
+        items.swap(i, j);
+    }
+}
+
+/// announces to `announce` (HTTP(S) or UDP) and returns the peers it advertises. Shared by
+/// `Client::get_peers` and the magnet link flow, which doesn't have a full `Torrent` yet.
+#[allow(clippy::too_many_arguments)]
+async fn get_peers_from_tracker(
+    announce: &reqwest::Url,
+    info_hash: hashes::Hash,
+    peer_id: &str,
+    port: u16,
+    uploaded: u64,
+    downloaded: u64,
+    left: u64,
+) -> anyhow::Result<Peers> {
+    if announce.scheme() == "udp" {
+        return udp_tracker::get_peers(announce, info_hash, peer_id, port, uploaded, downloaded, left)
+            .await;
+    }
+
+    let client = reqwest::Client::new();
+
+    let mut request = client.get(announce.clone()).build()?;
+
+    let query = serde_urlencoded::to_string(&TrackerRequest {
+        compact: 1,
+        downloaded: downloaded as usize,
+        left: left as usize,
+        peer_id: peer_id.to_owned(),
+        port,
+        uploaded: uploaded as usize,
+    })?;
+
+    request.url_mut().set_query(Some(&format!(
+        "info_hash={}&{}",
+        urlencode(&info_hash).as_str(),
+        query,
+    )));
+
+    let body = client.execute(request).await?.bytes().await?;
+
+    Ok(serde_bencode::from_bytes::<TrackerResponse>(body.as_ref())?.peers)
+}
+
 #[derive(Debug, Clone, Serialize)]
 struct TrackerRequest {
     peer_id: String,
@@ -222,6 +423,49 @@ mod hashes {
     }
 }
 
+mod raw_bencode {
+    /// Length in bytes of the single bencoded value starting at `buf[0]`, without decoding it.
+    pub(crate) fn value_len(buf: &[u8]) -> Option<usize> {
+        match *buf.first()? {
+            b'i' => Some(buf.iter().position(|&b| b == b'e')? + 1),
+            b'l' | b'd' => {
+                let mut pos = 1;
+                while *buf.get(pos)? != b'e' {
+                    pos += value_len(&buf[pos..])?;
+                }
+                Some(pos + 1)
+            }
+            b'0'..=b'9' => {
+                let colon = buf.iter().position(|&b| b == b':')?;
+                let len: usize = std::str::from_utf8(&buf[..colon]).ok()?.parse().ok()?;
+                Some(colon + 1 + len)
+            }
+            _ => None,
+        }
+    }
+
+    /// Finds the raw bencoded bytes of the value for `key` in the top-level dictionary
+    /// encoded in `buf`, without fully decoding the dictionary.
+    pub fn find_dict_value<'a>(buf: &'a [u8], key: &str) -> Option<&'a [u8]> {
+        if *buf.first()? != b'd' {
+            return None;
+        }
+        let needle = format!("{}:{}", key.len(), key);
+        let mut pos = 1;
+        while *buf.get(pos)? != b'e' {
+            let key_len = value_len(&buf[pos..])?;
+            let this_key = &buf[pos..pos + key_len];
+            pos += key_len;
+            let val_len = value_len(&buf[pos..])?;
+            if this_key == needle.as_bytes() {
+                return Some(&buf[pos..pos + val_len]);
+            }
+            pos += val_len;
+        }
+        None
+    }
+}
+
 mod peers {
     use serde::de::{self, Deserialize, Deserializer, Visitor};
     use std::fmt;
@@ -240,22 +484,18 @@ mod peers {
 
     #[derive(Debug, Clone)]
     pub struct Peers(pub Vec<Peer>);
-    struct PeersVisitor;
-    impl<'de> Visitor<'de> for PeersVisitor {
-        type Value = Peers;
-        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-            formatter.write_str("a byte string whose length is a multiple of 6")
-        }
-        fn visit_bytes<E>(self, v: &[u8]) -> Result<Self::Value, E>
-        where
-            E: de::Error,
-        {
-            if v.len() % 6 != 0 {
-                return Err(E::custom(format!("length is {}", v.len())));
+
+    impl Peers {
+        /// parses the "compact" representation used by both HTTP and UDP trackers: a byte
+        /// string that's a flat list of 4-byte IP + 2-byte port entries.
+        pub fn from_compact_bytes(bytes: &[u8]) -> Result<Self, String> {
+            if bytes.len() % 6 != 0 {
+                return Err(format!("length is {}", bytes.len()));
             }
             // TODO: use array_chunks when stable
             Ok(Peers(
-                v.chunks_exact(6)
+                bytes
+                    .chunks_exact(6)
                     .map(|slice_6| slice_6.try_into().expect("guaranteed to be length 6"))
                     .map(|chunk: [u8; 6]| {
                         let ip = format!("{}.{}.{}.{}", chunk[0], chunk[1], chunk[2], chunk[3]);
@@ -266,6 +506,20 @@ mod peers {
             ))
         }
     }
+
+    struct PeersVisitor;
+    impl<'de> Visitor<'de> for PeersVisitor {
+        type Value = Peers;
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("a byte string whose length is a multiple of 6")
+        }
+        fn visit_bytes<E>(self, v: &[u8]) -> Result<Self::Value, E>
+        where
+            E: de::Error,
+        {
+            Peers::from_compact_bytes(v).map_err(E::custom)
+        }
+    }
     impl<'de> Deserialize<'de> for Peers {
         fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
         where
@@ -276,6 +530,608 @@ mod peers {
     }
 }
 
+/// length-prefixed peer wire protocol messages, as sent after the handshake.
+mod message {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum MessageId {
+        Choke = 0,
+        Unchoke = 1,
+        Interested = 2,
+        NotInterested = 3,
+        Have = 4,
+        Bitfield = 5,
+        Request = 6,
+        Piece = 7,
+        Cancel = 8,
+        /// BEP 10 extension protocol message
+        Extended = 20,
+    }
+
+    impl MessageId {
+        fn from_u8(id: u8) -> anyhow::Result<Self> {
+            Ok(match id {
+                0 => Self::Choke,
+                1 => Self::Unchoke,
+                2 => Self::Interested,
+                3 => Self::NotInterested,
+                4 => Self::Have,
+                5 => Self::Bitfield,
+                6 => Self::Request,
+                7 => Self::Piece,
+                8 => Self::Cancel,
+                20 => Self::Extended,
+                other => anyhow::bail!("unknown peer message id {other}"),
+            })
+        }
+    }
+
+    #[derive(Debug, Clone)]
+    pub struct Message {
+        pub id: MessageId,
+        pub payload: Vec<u8>,
+    }
+
+    impl Message {
+        pub fn new(id: MessageId, payload: Vec<u8>) -> Self {
+            Self { id, payload }
+        }
+
+        /// reads a single message, transparently skipping zero-length keep-alives
+        pub async fn read(stream: &mut TcpStream) -> anyhow::Result<Self> {
+            loop {
+                let mut len_buf = [0u8; 4];
+                stream.read_exact(&mut len_buf).await?;
+                let len = u32::from_be_bytes(len_buf);
+                if len == 0 {
+                    continue;
+                }
+
+                let mut buf = vec![0u8; len as usize];
+                stream.read_exact(&mut buf).await?;
+                return Ok(Message::new(MessageId::from_u8(buf[0])?, buf[1..].to_vec()));
+            }
+        }
+
+        pub async fn write(&self, stream: &mut TcpStream) -> anyhow::Result<()> {
+            let len = 1 + self.payload.len() as u32;
+            stream.write_all(&len.to_be_bytes()).await?;
+            stream.write_all(&[self.id as u8]).await?;
+            stream.write_all(&self.payload).await?;
+            Ok(())
+        }
+    }
+}
+
+/// the peer handshake and the connection state needed to request pieces from a peer.
+mod peer {
+    use crate::hashes::Hash;
+    use crate::message::{Message, MessageId};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+
+    /// the size of the blocks we request pieces in, per the spec
+    pub const BLOCK_SIZE: u32 = 16 * 1024;
+
+    #[derive(Debug)]
+    pub struct Handshake {
+        pub reserved: [u8; 8],
+        pub info_hash: Hash,
+        pub peer_id: [u8; 20],
+    }
+
+    impl Handshake {
+        pub fn new(info_hash: Hash, peer_id: [u8; 20]) -> Self {
+            Self {
+                reserved: [0; 8],
+                info_hash,
+                peer_id,
+            }
+        }
+
+        /// sets the extension protocol bit (BEP 10), advertising support for extended messages
+        /// such as the `ut_metadata` exchange magnet links rely on.
+        pub fn with_extensions(mut self) -> Self {
+            self.reserved[5] |= 0x20;
+            self
+        }
+
+        pub fn supports_extensions(&self) -> bool {
+            self.reserved[5] & 0x20 != 0
+        }
+
+        pub fn to_bytes(&self) -> [u8; 68] {
+            let mut buf = [0u8; 68];
+            buf[0] = 19;
+            buf[1..20].copy_from_slice(b"BitTorrent protocol");
+            buf[20..28].copy_from_slice(&self.reserved);
+            buf[28..48].copy_from_slice(&self.info_hash);
+            buf[48..68].copy_from_slice(&self.peer_id);
+            buf
+        }
+
+        pub fn from_bytes(buf: &[u8; 68]) -> anyhow::Result<Self> {
+            anyhow::ensure!(
+                buf[0] == 19 && &buf[1..20] == b"BitTorrent protocol",
+                "not a BitTorrent handshake"
+            );
+            Ok(Self {
+                reserved: buf[20..28].try_into().unwrap(),
+                info_hash: buf[28..48].try_into().unwrap(),
+                peer_id: buf[48..68].try_into().unwrap(),
+            })
+        }
+    }
+
+    /// an established connection to a peer, past the handshake and the initial
+    /// bitfield/interested/unchoke exchange, ready to request pieces.
+    pub struct PeerConnection {
+        stream: TcpStream,
+    }
+
+    impl PeerConnection {
+        pub async fn connect(addr: &str, info_hash: Hash, peer_id: [u8; 20]) -> anyhow::Result<Self> {
+            let mut stream = TcpStream::connect(addr).await?;
+
+            stream
+                .write_all(&Handshake::new(info_hash, peer_id).to_bytes())
+                .await?;
+            let mut response_buf = [0u8; 68];
+            stream.read_exact(&mut response_buf).await?;
+            let response = Handshake::from_bytes(&response_buf)?;
+            anyhow::ensure!(
+                response.info_hash == info_hash,
+                "peer handshake returned a different info hash"
+            );
+
+            let bitfield = Message::read(&mut stream).await?;
+            anyhow::ensure!(
+                bitfield.id == MessageId::Bitfield,
+                "expected a bitfield message, got {:?}",
+                bitfield.id
+            );
+
+            Message::new(MessageId::Interested, Vec::new())
+                .write(&mut stream)
+                .await?;
+            let unchoke = Message::read(&mut stream).await?;
+            anyhow::ensure!(
+                unchoke.id == MessageId::Unchoke,
+                "expected an unchoke message, got {:?}",
+                unchoke.id
+            );
+
+            Ok(Self { stream })
+        }
+
+        /// requests the given piece in `BLOCK_SIZE` blocks and reassembles the `piece`
+        /// messages. Does not verify the piece hash; the caller does that.
+        pub async fn download_piece(
+            &mut self,
+            index: u32,
+            piece_length: u32,
+        ) -> anyhow::Result<Vec<u8>> {
+            let mut piece = vec![0u8; piece_length as usize];
+            let mut offset = 0;
+            while offset < piece_length {
+                let block_length = BLOCK_SIZE.min(piece_length - offset);
+
+                let mut payload = Vec::with_capacity(12);
+                payload.extend_from_slice(&index.to_be_bytes());
+                payload.extend_from_slice(&offset.to_be_bytes());
+                payload.extend_from_slice(&block_length.to_be_bytes());
+                Message::new(MessageId::Request, payload)
+                    .write(&mut self.stream)
+                    .await?;
+
+                // a conformant seeder may interleave control messages (e.g. `have`,
+                // `choke`/`unchoke`) between the piece blocks we asked for; skip those
+                // instead of treating them as a protocol error.
+                let message = loop {
+                    let message = Message::read(&mut self.stream).await?;
+                    if message.id == MessageId::Piece {
+                        break message;
+                    }
+                };
+                let block_index = u32::from_be_bytes(message.payload[0..4].try_into()?);
+                let block_begin = u32::from_be_bytes(message.payload[4..8].try_into()?);
+                anyhow::ensure!(block_index == index, "piece message was for the wrong piece");
+                let block_data = &message.payload[8..];
+                piece[block_begin as usize..block_begin as usize + block_data.len()]
+                    .copy_from_slice(block_data);
+
+                offset += block_length;
+            }
+            Ok(piece)
+        }
+    }
+
+    /// the extended message id the handshake itself is always sent/received on (BEP 10)
+    const EXTENDED_HANDSHAKE_ID: u8 = 0;
+    /// the extended message id we advertise for `ut_metadata` (BEP 9); arbitrary, just needs
+    /// to be consistent between our handshake and how we interpret the peer's replies
+    const UT_METADATA_ID: u8 = 1;
+
+    #[derive(serde::Serialize, Debug)]
+    struct ExtendedHandshakePayload {
+        m: UtMetadataExtensionId,
+    }
+
+    #[derive(serde::Serialize, serde::Deserialize, Debug, Default)]
+    struct UtMetadataExtensionId {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        ut_metadata: Option<u8>,
+    }
+
+    #[derive(serde::Deserialize, Debug)]
+    struct PeerExtendedHandshake {
+        #[serde(default)]
+        m: UtMetadataExtensionId,
+        #[serde(default)]
+        metadata_size: Option<i64>,
+    }
+
+    #[derive(serde::Serialize, Debug)]
+    struct UtMetadataRequest {
+        msg_type: u8,
+        piece: i64,
+    }
+
+    #[derive(serde::Deserialize, Debug)]
+    struct UtMetadataResponse {
+        msg_type: u8,
+        piece: i64,
+    }
+
+    /// retrieves the info dict from a peer via the extension protocol (BEP 9 over BEP 10):
+    /// exchange extended handshakes advertising `ut_metadata`, then request the info dict in
+    /// `BLOCK_SIZE` pieces and reassemble them. Unlike `PeerConnection::connect`, this doesn't
+    /// wait for a bitfield or send `interested`/expect `unchoke` - we're not downloading pieces,
+    /// and the peer may not even have the actual file data yet.
+    pub async fn fetch_metadata(addr: &str, info_hash: Hash, peer_id: [u8; 20]) -> anyhow::Result<Vec<u8>> {
+        let mut stream = TcpStream::connect(addr).await?;
+
+        let handshake = Handshake::new(info_hash, peer_id).with_extensions();
+        stream.write_all(&handshake.to_bytes()).await?;
+        let mut response_buf = [0u8; 68];
+        stream.read_exact(&mut response_buf).await?;
+        let response = Handshake::from_bytes(&response_buf)?;
+        anyhow::ensure!(
+            response.info_hash == info_hash,
+            "peer handshake returned a different info hash"
+        );
+        anyhow::ensure!(
+            response.supports_extensions(),
+            "peer does not support the extension protocol"
+        );
+
+        let mut payload = vec![EXTENDED_HANDSHAKE_ID];
+        payload.extend(serde_bencode::to_bytes(&ExtendedHandshakePayload {
+            m: UtMetadataExtensionId {
+                ut_metadata: Some(UT_METADATA_ID),
+            },
+        })?);
+        Message::new(MessageId::Extended, payload)
+            .write(&mut stream)
+            .await?;
+
+        // the peer may send a bitfield first; skip anything that isn't the extended handshake.
+        let (peer_ut_metadata_id, metadata_size) = loop {
+            let message = Message::read(&mut stream).await?;
+            if message.id != MessageId::Extended
+                || message.payload.first() != Some(&EXTENDED_HANDSHAKE_ID)
+            {
+                continue;
+            }
+            let handshake: PeerExtendedHandshake = serde_bencode::from_bytes(&message.payload[1..])?;
+            let ut_metadata_id = handshake
+                .m
+                .ut_metadata
+                .ok_or_else(|| anyhow::anyhow!("peer does not support ut_metadata"))?;
+            let metadata_size = handshake
+                .metadata_size
+                .ok_or_else(|| anyhow::anyhow!("peer did not advertise a metadata_size"))?;
+            break (ut_metadata_id, metadata_size as usize);
+        };
+
+        let piece_count = metadata_size.div_ceil(BLOCK_SIZE as usize);
+        let mut metadata = vec![0u8; metadata_size];
+        for piece in 0..piece_count {
+            let mut payload = vec![peer_ut_metadata_id];
+            payload.extend(serde_bencode::to_bytes(&UtMetadataRequest {
+                msg_type: 0,
+                piece: piece as i64,
+            })?);
+            Message::new(MessageId::Extended, payload)
+                .write(&mut stream)
+                .await?;
+
+            let message = loop {
+                let message = Message::read(&mut stream).await?;
+                if message.id == MessageId::Extended
+                    && message.payload.first() == Some(&UT_METADATA_ID)
+                {
+                    break message;
+                }
+            };
+
+            let dict_bytes = &message.payload[1..];
+            let dict_len = crate::raw_bencode::value_len(dict_bytes)
+                .ok_or_else(|| anyhow::anyhow!("malformed ut_metadata response"))?;
+            let response: UtMetadataResponse = serde_bencode::from_bytes(&dict_bytes[..dict_len])?;
+            anyhow::ensure!(
+                response.msg_type == 1,
+                "peer rejected metadata request for piece {piece}"
+            );
+            anyhow::ensure!(
+                response.piece as usize == piece,
+                "metadata piece arrived out of order"
+            );
+
+            let data = &dict_bytes[dict_len..];
+            let begin = piece * BLOCK_SIZE as usize;
+            metadata[begin..begin + data.len()].copy_from_slice(data);
+        }
+
+        Ok(metadata)
+    }
+}
+
+/// UDP tracker protocol (BEP 15), for `udp://` announce URLs that `reqwest` can't handle.
+mod udp_tracker {
+    use crate::peers::Peers;
+    use std::time::Duration;
+    use tokio::net::UdpSocket;
+    use tokio::time::{timeout, Instant};
+
+    const PROTOCOL_ID: u64 = 0x41727101980;
+    const ACTION_CONNECT: u32 = 0;
+    const ACTION_ANNOUNCE: u32 = 1;
+
+    /// per-attempt cap on the spec's exponential backoff (15 * 2^n seconds), so several
+    /// retransmits still fit inside `MAX_TOTAL_WAIT` instead of the first interval alone
+    /// exhausting it.
+    const MAX_ATTEMPT_WAIT: Duration = Duration::from_secs(3);
+    /// overall deadline for a single tracker exchange. the spec's backoff over 8 attempts
+    /// would otherwise let one dead `udp://` tracker stall the caller for up to an hour;
+    /// capping the total wait instead lets a dead tier degrade to the next tracker in
+    /// seconds, while still allowing several retransmits to a live-but-slow one.
+    const MAX_TOTAL_WAIT: Duration = Duration::from_secs(20);
+
+    fn random_transaction_id() -> u32 {
+        crate::random_u64() as u32
+    }
+
+    /// sends `packet` and waits for a response, retrying with the spec's exponential backoff
+    /// (capped per attempt by `MAX_ATTEMPT_WAIT`) until one arrives, `MAX_TOTAL_WAIT` is
+    /// exhausted, or we give up after 8 attempts.
+    async fn send_and_receive(
+        socket: &UdpSocket,
+        packet: &[u8],
+        response_buf: &mut [u8],
+    ) -> anyhow::Result<usize> {
+        let deadline = Instant::now() + MAX_TOTAL_WAIT;
+        for attempt in 0..8u32 {
+            let wait = Duration::from_secs(15 * 2u64.pow(attempt))
+                .min(MAX_ATTEMPT_WAIT)
+                .min(deadline.saturating_duration_since(Instant::now()));
+            if wait.is_zero() {
+                break;
+            }
+
+            socket.send(packet).await?;
+            if let Ok(received) = timeout(wait, socket.recv(response_buf)).await {
+                return Ok(received?);
+            }
+        }
+        anyhow::bail!("UDP tracker did not respond within {MAX_TOTAL_WAIT:?}")
+    }
+
+    async fn connect(socket: &UdpSocket) -> anyhow::Result<u64> {
+        let transaction_id = random_transaction_id();
+        let mut request = Vec::with_capacity(16);
+        request.extend_from_slice(&PROTOCOL_ID.to_be_bytes());
+        request.extend_from_slice(&ACTION_CONNECT.to_be_bytes());
+        request.extend_from_slice(&transaction_id.to_be_bytes());
+
+        let mut response = [0u8; 16];
+        let len = send_and_receive(socket, &request, &mut response).await?;
+        anyhow::ensure!(len >= 16, "connect response too short");
+        anyhow::ensure!(
+            u32::from_be_bytes(response[0..4].try_into()?) == ACTION_CONNECT,
+            "unexpected action in connect response"
+        );
+        anyhow::ensure!(
+            u32::from_be_bytes(response[4..8].try_into()?) == transaction_id,
+            "transaction id mismatch in connect response"
+        );
+        Ok(u64::from_be_bytes(response[8..16].try_into()?))
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn announce(
+        socket: &UdpSocket,
+        connection_id: u64,
+        info_hash: crate::hashes::Hash,
+        peer_id: &str,
+        port: u16,
+        uploaded: u64,
+        downloaded: u64,
+        left: u64,
+    ) -> anyhow::Result<Peers> {
+        let transaction_id = random_transaction_id();
+        let mut request = Vec::with_capacity(98);
+        request.extend_from_slice(&connection_id.to_be_bytes());
+        request.extend_from_slice(&ACTION_ANNOUNCE.to_be_bytes());
+        request.extend_from_slice(&transaction_id.to_be_bytes());
+        request.extend_from_slice(&info_hash);
+        request.extend_from_slice(peer_id.as_bytes());
+        request.extend_from_slice(&(downloaded as i64).to_be_bytes());
+        request.extend_from_slice(&(left as i64).to_be_bytes());
+        request.extend_from_slice(&(uploaded as i64).to_be_bytes());
+        request.extend_from_slice(&0i32.to_be_bytes()); // event: none
+        request.extend_from_slice(&0u32.to_be_bytes()); // IP: let the tracker decide
+        request.extend_from_slice(&random_transaction_id().to_be_bytes()); // key
+        request.extend_from_slice(&(-1i32).to_be_bytes()); // num_want: as many as possible
+        request.extend_from_slice(&port.to_be_bytes());
+
+        let mut response = [0u8; 2048];
+        let len = send_and_receive(socket, &request, &mut response).await?;
+        anyhow::ensure!(len >= 20, "announce response too short");
+        anyhow::ensure!(
+            u32::from_be_bytes(response[0..4].try_into()?) == ACTION_ANNOUNCE,
+            "unexpected action in announce response"
+        );
+        anyhow::ensure!(
+            u32::from_be_bytes(response[4..8].try_into()?) == transaction_id,
+            "transaction id mismatch in announce response"
+        );
+
+        Peers::from_compact_bytes(&response[20..len]).map_err(|e| anyhow::anyhow!(e))
+    }
+
+    /// performs the connect/announce handshake against a `udp://` tracker and returns the
+    /// peers it advertises, in the same shape an HTTP tracker would.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn get_peers(
+        announce_url: &reqwest::Url,
+        info_hash: crate::hashes::Hash,
+        peer_id: &str,
+        port: u16,
+        uploaded: u64,
+        downloaded: u64,
+        left: u64,
+    ) -> anyhow::Result<Peers> {
+        let host = announce_url
+            .host_str()
+            .ok_or_else(|| anyhow::anyhow!("UDP tracker URL has no host"))?;
+        let tracker_port = announce_url
+            .port()
+            .ok_or_else(|| anyhow::anyhow!("UDP tracker URL has no port"))?;
+
+        let socket = UdpSocket::bind("0.0.0.0:0").await?;
+        socket.connect((host, tracker_port)).await?;
+
+        let connection_id = connect(&socket).await?;
+        announce(
+            &socket,
+            connection_id,
+            info_hash,
+            peer_id,
+            port,
+            uploaded,
+            downloaded,
+            left,
+        )
+        .await
+    }
+}
+
+/// parsing of `magnet:?xt=urn:btih:...` links.
+mod magnet {
+    use crate::hashes::Hash;
+
+    #[derive(Debug)]
+    pub struct MagnetLink {
+        pub info_hash: Hash,
+        pub display_name: Option<String>,
+        pub trackers: Vec<reqwest::Url>,
+    }
+
+    impl MagnetLink {
+        pub fn parse(uri: &str) -> anyhow::Result<Self> {
+            let query = uri
+                .strip_prefix("magnet:?")
+                .ok_or_else(|| anyhow::anyhow!("not a magnet link"))?;
+
+            let mut info_hash = None;
+            let mut display_name = None;
+            let mut trackers = Vec::new();
+            for (key, value) in serde_urlencoded::from_str::<Vec<(String, String)>>(query)? {
+                match key.as_str() {
+                    "xt" => {
+                        let hash = value
+                            .strip_prefix("urn:btih:")
+                            .ok_or_else(|| anyhow::anyhow!("unsupported xt value: {value}"))?;
+                        info_hash = Some(decode_info_hash(hash)?);
+                    }
+                    "dn" => display_name = Some(value),
+                    "tr" => trackers.push(reqwest::Url::parse(&value)?),
+                    _ => {}
+                }
+            }
+
+            Ok(Self {
+                info_hash: info_hash
+                    .ok_or_else(|| anyhow::anyhow!("magnet link has no xt=urn:btih: parameter"))?,
+                display_name,
+                trackers,
+            })
+        }
+    }
+
+    fn decode_info_hash(s: &str) -> anyhow::Result<Hash> {
+        match s.len() {
+            40 => hex::decode(s)?
+                .try_into()
+                .map_err(|_| anyhow::anyhow!("hex info hash did not decode to 20 bytes")),
+            32 => decode_base32(s),
+            other => anyhow::bail!("info hash has unexpected length {other}"),
+        }
+    }
+
+    /// decodes an RFC 4648 base32 string (no padding) into a 20-byte info hash
+    fn decode_base32(s: &str) -> anyhow::Result<Hash> {
+        const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+        let mut bits: u64 = 0;
+        let mut bit_count = 0u32;
+        let mut out = Vec::with_capacity(20);
+        for c in s.to_ascii_uppercase().bytes() {
+            let value = ALPHABET
+                .iter()
+                .position(|&a| a == c)
+                .ok_or_else(|| anyhow::anyhow!("invalid base32 character '{}'", c as char))?;
+            bits = (bits << 5) | value as u64;
+            bit_count += 5;
+            if bit_count >= 8 {
+                bit_count -= 8;
+                out.push((bits >> bit_count) as u8);
+            }
+        }
+        out.try_into()
+            .map_err(|_| anyhow::anyhow!("base32 info hash did not decode to 20 bytes"))
+    }
+}
+
+/// expected length of `piece_index`, accounting for the final piece usually being shorter
+/// than `piece_length`.
+fn expected_piece_length(torrent: &Torrent, piece_index: usize) -> u64 {
+    let piece_count = torrent.info.pieces.0.len();
+    if piece_index == piece_count - 1 {
+        let remainder = torrent.info.total_length() % torrent.info.piece_length;
+        if remainder == 0 {
+            torrent.info.piece_length
+        } else {
+            remainder
+        }
+    } else {
+        torrent.info.piece_length
+    }
+}
+
+fn verify_piece(torrent: &Torrent, piece_index: usize, data: &[u8]) -> anyhow::Result<()> {
+    let mut hasher = Sha1::new();
+    hasher.update(data);
+    let actual_hash: hashes::Hash = hasher.finalize().into();
+    anyhow::ensure!(
+        actual_hash == torrent.info.pieces.0[piece_index],
+        "piece {piece_index} failed hash verification"
+    );
+    Ok(())
+}
+
 // Usage: your_bittorrent.sh decode "<encoded_value>"
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
@@ -288,30 +1144,183 @@ async fn main() -> anyhow::Result<()> {
         }
         Command::Info { torrent } => {
             let torrent_file = std::fs::read(torrent)?;
-            let torrent: Torrent = serde_bencode::from_bytes(&torrent_file)?;
-            println!("Tracker URL: {}", torrent.announce.value());
-            println!("Length: {}", torrent.info.length);
-            println!("Info Hash: {}", hex::encode(torrent.info_hash()));
-            println!("Piece Length: {}", torrent.info.piece_length);
-            println!("Piece Hashes:");
-            for hash in torrent.info.pieces.0 {
-                println!("{}", hex::encode(hash));
-            }
+            let torrent = Torrent::from_bytes(&torrent_file)?;
+            print_torrent_info(&torrent);
         }
         Command::Peers { torrent } => {
             let torrent_file = std::fs::read(torrent)?;
-            let torrent: Torrent = serde_bencode::from_bytes(&torrent_file)?;
+            let torrent = Torrent::from_bytes(&torrent_file)?;
 
-            let client = Client::new(&torrent);
+            let mut client = Client::new(&torrent);
             for peer in client.get_peers().await?.0 {
                 println!("{}", peer.to_string());
             }
         }
+        Command::DownloadPiece {
+            output,
+            torrent,
+            piece,
+        } => {
+            let torrent_file = std::fs::read(torrent)?;
+            let torrent = Torrent::from_bytes(&torrent_file)?;
+
+            let mut client = Client::new(&torrent);
+            let peers = client.get_peers().await?;
+            let peer_addr = peers
+                .0
+                .first()
+                .ok_or_else(|| anyhow::anyhow!("tracker returned no peers"))?
+                .to_string();
+
+            let mut connection =
+                PeerConnection::connect(&peer_addr, torrent.info_hash(), client.peer_id_bytes()?)
+                    .await?;
+            let piece_length = expected_piece_length(&torrent, piece);
+            let data = connection
+                .download_piece(piece as u32, piece_length as u32)
+                .await?;
+            verify_piece(&torrent, piece, &data)?;
+
+            std::fs::write(&output, data)?;
+            println!("Piece {} downloaded to {}.", piece, output.display());
+        }
+        Command::Download { output, torrent } => {
+            let torrent_file = std::fs::read(torrent)?;
+            let torrent = Torrent::from_bytes(&torrent_file)?;
+
+            let mut client = Client::new(&torrent);
+            let peers = client.get_peers().await?;
+            let peer_addr = peers
+                .0
+                .first()
+                .ok_or_else(|| anyhow::anyhow!("tracker returned no peers"))?
+                .to_string();
+
+            let mut connection =
+                PeerConnection::connect(&peer_addr, torrent.info_hash(), client.peer_id_bytes()?)
+                    .await?;
+            let mut file = Vec::with_capacity(torrent.info.total_length() as usize);
+            for piece_index in 0..torrent.info.pieces.0.len() {
+                let piece_length = expected_piece_length(&torrent, piece_index);
+                let data = connection
+                    .download_piece(piece_index as u32, piece_length as u32)
+                    .await?;
+                verify_piece(&torrent, piece_index, &data)?;
+                file.extend(data);
+            }
+
+            std::fs::write(&output, file)?;
+            println!("Downloaded {} to {}.", torrent.info.name, output.display());
+        }
+        Command::Magnet { uri, output } => {
+            let magnet = magnet::MagnetLink::parse(&uri)?;
+            if let Some(name) = &magnet.display_name {
+                println!("Display Name: {name}");
+            }
+            let tracker = magnet
+                .trackers
+                .first()
+                .ok_or_else(|| anyhow::anyhow!("magnet link has no trackers"))?;
+
+            let peer_id_str = generate_peer_id(DEFAULT_PEER_ID_PREFIX);
+            let peer_id: [u8; 20] = peer_id_str
+                .as_bytes()
+                .try_into()
+                .map_err(|_| anyhow::anyhow!("peer id must be exactly 20 bytes"))?;
+
+            // we don't know the torrent's size yet, so announce as if there's still
+            // everything left to download
+            let peers = get_peers_from_tracker(tracker, magnet.info_hash, &peer_id_str, 6881, 0, 0, 1)
+                .await?;
+            let peer_addr = peers
+                .0
+                .first()
+                .ok_or_else(|| anyhow::anyhow!("tracker returned no peers"))?
+                .to_string();
+
+            let info_raw = peer::fetch_metadata(&peer_addr, magnet.info_hash, peer_id).await?;
+
+            let mut hasher = Sha1::new();
+            hasher.update(&info_raw);
+            let actual_hash: hashes::Hash = hasher.finalize().into();
+            anyhow::ensure!(
+                actual_hash == magnet.info_hash,
+                "fetched metadata does not match the magnet link's info hash"
+            );
+
+            let info: Info = serde_bencode::from_bytes(&info_raw)?;
+            info.validate()?;
+            let torrent = Torrent {
+                announce: Url(tracker.clone()),
+                announce_list: None,
+                info,
+                info_raw,
+            };
+
+            print_torrent_info(&torrent);
+
+            // from here on, the resolved torrent flows through the same Client/download
+            // paths a .torrent file would, so peers and download work from a magnet link too.
+            let mut client = Client::new(&torrent);
+            let download_peers = client.get_peers().await?;
+            for peer in &download_peers.0 {
+                println!("{}", peer.to_string());
+            }
+
+            if let Some(output) = output {
+                let peer_addr = download_peers
+                    .0
+                    .first()
+                    .ok_or_else(|| anyhow::anyhow!("tracker returned no peers"))?
+                    .to_string();
+
+                let mut connection = PeerConnection::connect(
+                    &peer_addr,
+                    torrent.info_hash(),
+                    client.peer_id_bytes()?,
+                )
+                .await?;
+                let mut file = Vec::with_capacity(torrent.info.total_length() as usize);
+                for piece_index in 0..torrent.info.pieces.0.len() {
+                    let piece_length = expected_piece_length(&torrent, piece_index);
+                    let data = connection
+                        .download_piece(piece_index as u32, piece_length as u32)
+                        .await?;
+                    verify_piece(&torrent, piece_index, &data)?;
+                    file.extend(data);
+                }
+
+                std::fs::write(&output, file)?;
+                println!("Downloaded {} to {}.", torrent.info.name, output.display());
+            }
+        }
     }
 
     Ok(())
 }
 
+fn print_torrent_info(torrent: &Torrent) {
+    println!("Tracker URL: {}", torrent.announce.value());
+    println!("Length: {}", torrent.info.total_length());
+    println!("Info Hash: {}", hex::encode(torrent.info_hash()));
+    println!("Piece Length: {}", torrent.info.piece_length);
+    println!("Piece Hashes:");
+    for hash in &torrent.info.pieces.0 {
+        println!("{}", hex::encode(hash));
+    }
+    if let Some(files) = &torrent.info.files {
+        println!("Files:");
+        for file in files {
+            println!(
+                "{}/{}: {}",
+                torrent.info.name,
+                file.path.join("/"),
+                file.length
+            );
+        }
+    }
+}
+
 fn urlencode(t: &[u8; 20]) -> String {
     let mut encoded = String::with_capacity(3 * t.len());
     for &byte in t {